@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use pingora::cache::cache_control::CacheControl;
+use pingora::cache::eviction::simple_lru::Manager as LruEvictionManager;
+use pingora::cache::lock::CacheLock;
+use pingora::cache::{CacheKey, CacheMeta, MemCache, NoCacheReason, RespCacheable};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+
+use crate::api::schema::Method;
+use crate::EncoreName;
+
+/// Configuration for a service's opt-in response cache.
+#[derive(Clone, Debug)]
+pub struct CacheConfig {
+    /// Maximum number of bytes the in-memory cache will retain before
+    /// evicting the least-recently-used entries.
+    pub max_bytes: usize,
+    /// Freshness lifetime applied to upstream responses that don't carry
+    /// their own `Cache-Control` freshness directive.
+    pub default_ttl: Duration,
+    /// How long a request will wait on another in-flight request for the
+    /// same cache key before giving up and going to the upstream itself.
+    pub lock_timeout: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: 64 * 1024 * 1024,
+            default_ttl: Duration::from_secs(60),
+            lock_timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+/// The gateway's in-memory HTTP response cache, shared across requests.
+///
+/// Caching is opt-in per route: routes that aren't configured for caching
+/// never touch this at all (see [`GatewayCache::enable_for_service`]).
+pub struct GatewayCache {
+    storage: MemCache,
+    eviction: LruEvictionManager,
+    lock: CacheLock,
+    config: CacheConfig,
+    enabled_services: HashMap<EncoreName, CacheConfig>,
+    /// Per-route opt-ins, layered on top of `enabled_services`: a
+    /// `(service, path_prefix)` pair enables caching for just the routes
+    /// under that prefix, even when the service as a whole isn't opted in.
+    enabled_routes: Vec<(EncoreName, String, CacheConfig)>,
+}
+
+/// Methods safe to serve from cache: a cached entry is shared by every
+/// caller whose request maps to the same key, so only requests with no side
+/// effects and no request body that could change the response are eligible.
+fn is_cacheable_method(method: &Method) -> bool {
+    matches!(method, Method::GET | Method::HEAD)
+}
+
+impl GatewayCache {
+    pub fn new(default_config: CacheConfig) -> Self {
+        Self {
+            storage: MemCache::new(),
+            eviction: LruEvictionManager::new(default_config.max_bytes),
+            lock: CacheLock::new(default_config.lock_timeout),
+            config: default_config,
+            enabled_services: HashMap::new(),
+            enabled_routes: Vec::new(),
+        }
+    }
+
+    /// Marks a service's routes as cacheable, optionally with config
+    /// overriding the gateway-wide defaults. Only the override's
+    /// `default_ttl` takes effect; `max_bytes` and `lock_timeout` always
+    /// come from the config passed to [`GatewayCache::new`].
+    pub fn enable_for_service(&mut self, service: EncoreName, config: Option<CacheConfig>) {
+        self.enabled_services
+            .insert(service, config.unwrap_or_else(|| self.config.clone()));
+    }
+
+    /// Marks just the routes under `path_prefix` on `service` as cacheable,
+    /// for services where only some routes are safe to cache. As with
+    /// [`GatewayCache::enable_for_service`], only the override's
+    /// `default_ttl` takes effect.
+    pub fn enable_for_route(
+        &mut self,
+        service: EncoreName,
+        path_prefix: impl Into<String>,
+        config: Option<CacheConfig>,
+    ) {
+        self.enabled_routes.push((
+            service,
+            path_prefix.into(),
+            config.unwrap_or_else(|| self.config.clone()),
+        ));
+    }
+
+    /// Whether `method`+`path` on `service` is eligible for caching at all:
+    /// opted in (by service or route) and using a cacheable method.
+    pub fn is_enabled_for(&self, service: &EncoreName, method: &Method, path: &str) -> bool {
+        is_cacheable_method(method) && self.policy_for(service, path).is_some()
+    }
+
+    fn policy_for(&self, service: &EncoreName, path: &str) -> Option<&CacheConfig> {
+        self.enabled_routes
+            .iter()
+            .find(|(s, prefix, _)| s == service && path.starts_with(prefix.as_str()))
+            .map(|(_, _, config)| config)
+            .or_else(|| self.enabled_services.get(service))
+    }
+
+    /// Enables caching for this request on `session`, storing into this
+    /// gateway's shared memory cache and eviction manager.
+    ///
+    /// The gateway leaks its `GatewayCache` for the lifetime of the process
+    /// (see [`super::Gateway::new`]), so `self` here is always `&'static`.
+    pub fn enable(&'static self, session: &mut Session) {
+        session.cache.enable(
+            &self.storage,
+            Some(&self.eviction),
+            None,
+            Some(&self.lock),
+            None,
+        );
+    }
+
+    /// Builds the primary cache key for a request from its service, method,
+    /// and routed path *and query string* — two requests that differ only
+    /// by query (e.g. `/search?q=foo` vs `/search?q=bar`) must not collide
+    /// into the same entry. Variance across `Vary`-named headers is layered
+    /// on top by pingora itself via [`vary_values`] / `cache_vary_filter`.
+    pub fn cache_key(&self, service: &EncoreName, method: &Method, path_and_query: &str) -> CacheKey {
+        CacheKey::new(
+            service.to_string(),
+            format!("{}:{path_and_query}", method.as_str()),
+            "",
+        )
+    }
+
+    /// Decides whether an upstream response may be cached, honoring
+    /// `no-store`/`private`/`max-age` and falling back to the configured
+    /// default TTL when the response carries no freshness directive.
+    pub fn resp_cacheable(
+        &self,
+        service: &EncoreName,
+        path: &str,
+        resp: &ResponseHeader,
+    ) -> RespCacheable {
+        let cc = CacheControl::from_resp_headers(resp);
+
+        if let Some(cc) = &cc {
+            if cc.no_store() || cc.private() {
+                return RespCacheable::Uncacheable(NoCacheReason::OriginNotCache);
+            }
+        }
+
+        let default_ttl = self
+            .policy_for(service, path)
+            .unwrap_or(&self.config)
+            .default_ttl;
+        let fresh_for = cc
+            .as_ref()
+            .and_then(|cc| cc.max_age().ok().flatten())
+            .unwrap_or(default_ttl);
+
+        let now = SystemTime::now();
+        let meta = CacheMeta::new(now + fresh_for, now, 0, 0, resp.clone());
+        RespCacheable::Cacheable(meta)
+    }
+}
+
+/// Parses the upstream's `Vary` header into the list of header names whose
+/// values become part of the cache key's variance dimension.
+pub fn vary_header_names(resp: &ResponseHeader) -> Vec<String> {
+    resp.headers
+        .get(http::header::VARY)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| {
+            v.split(',')
+                .map(|name| name.trim().to_ascii_lowercase())
+                .filter(|name| name != "*")
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Reads the current values of `names` off an inbound request, for use in
+/// `cache_vary_filter` to build the per-request variance map pingora needs
+/// to distinguish cached entries that vary by header.
+pub fn vary_values(
+    req: &pingora::http::RequestHeader,
+    names: &[String],
+) -> HashMap<String, Option<String>> {
+    names
+        .iter()
+        .map(|name| {
+            let value = req
+                .headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string);
+            (name.clone(), value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora::http::RequestHeader;
+
+    fn resp_with_vary(value: &str) -> ResponseHeader {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header(http::header::VARY, value).unwrap();
+        resp
+    }
+
+    #[test]
+    fn vary_header_names_splits_lowercases_and_drops_wildcard() {
+        let resp = resp_with_vary("Accept-Encoding, X-Api-Key, *");
+        assert_eq!(
+            vary_header_names(&resp),
+            vec!["accept-encoding".to_string(), "x-api-key".to_string()]
+        );
+    }
+
+    #[test]
+    fn vary_header_names_empty_without_header() {
+        let resp = ResponseHeader::build(200, None).unwrap();
+        assert!(vary_header_names(&resp).is_empty());
+    }
+
+    #[test]
+    fn vary_values_reads_present_headers_and_none_for_missing() {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header("accept-encoding", "gzip").unwrap();
+
+        let values = vary_values(
+            &req,
+            &["accept-encoding".to_string(), "x-api-key".to_string()],
+        );
+
+        assert_eq!(
+            values.get("accept-encoding").unwrap().as_deref(),
+            Some("gzip")
+        );
+        assert_eq!(values.get("x-api-key").unwrap(), &None);
+    }
+
+    #[test]
+    fn cache_key_differs_by_method_and_query_string() {
+        let cache = GatewayCache::new(CacheConfig::default());
+        let service = EncoreName::from("svc-a");
+
+        let get_key = cache.cache_key(&service, &Method::GET, "/search?q=foo");
+        let other_query_key = cache.cache_key(&service, &Method::GET, "/search?q=bar");
+        let head_key = cache.cache_key(&service, &Method::HEAD, "/search?q=foo");
+
+        // `CacheKey` doesn't expose its parts directly; comparing the debug
+        // representation is enough to confirm query string and method are
+        // both folded into the key rather than dropped.
+        assert_ne!(format!("{get_key:?}"), format!("{other_query_key:?}"));
+        assert_ne!(format!("{get_key:?}"), format!("{head_key:?}"));
+    }
+
+    #[test]
+    fn is_enabled_for_requires_cacheable_method_and_opt_in() {
+        let mut cache = GatewayCache::new(CacheConfig::default());
+        let service = EncoreName::from("svc-a");
+        cache.enable_for_service(service.clone(), None);
+
+        assert!(cache.is_enabled_for(&service, &Method::GET, "/anything"));
+        assert!(!cache.is_enabled_for(&service, &Method::POST, "/anything"));
+        assert!(!cache.is_enabled_for(&EncoreName::from("svc-b"), &Method::GET, "/anything"));
+    }
+
+    #[test]
+    fn is_enabled_for_route_scopes_to_path_prefix() {
+        let mut cache = GatewayCache::new(CacheConfig::default());
+        let service = EncoreName::from("svc-a");
+        cache.enable_for_route(service.clone(), "/public", None);
+
+        assert!(cache.is_enabled_for(&service, &Method::GET, "/public/widgets"));
+        assert!(!cache.is_enabled_for(&service, &Method::GET, "/private/widgets"));
+    }
+}
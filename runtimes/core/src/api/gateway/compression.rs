@@ -0,0 +1,408 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use pingora::http::{RequestHeader, ResponseHeader};
+
+use crate::EncoreName;
+
+/// Minimum response body size, in bytes, below which compression isn't
+/// worth the CPU cost.
+const DEFAULT_MIN_COMPRESS_BYTES: usize = 1024;
+
+/// Upper bound, in bytes, on how much of a single response body the gateway
+/// will buffer in order to compress/decompress it. One `Gateway` proxies
+/// every service, so buffering without a cap is a shared-fate memory risk
+/// for the whole process; a response past this size is proxied as an error
+/// instead of being transformed.
+const DEFAULT_MAX_BUFFER_BYTES: usize = 16 * 1024 * 1024;
+
+/// Content types that are already compressed (or otherwise not worth
+/// re-compressing) and are skipped even if they clear the size threshold.
+/// This is just the default `CompressionConfig::skip_content_type_prefixes`;
+/// callers can override it per gateway or per service.
+const DEFAULT_SKIP_CONTENT_TYPE_PREFIXES: &[&str] = &[
+    "image/", "video/", "audio/", "font/", "application/zip", "application/gzip",
+    "application/x-7z-compressed", "application/x-rar",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Brotli,
+    Zstd,
+    Deflate,
+}
+
+impl Encoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CompressionConfig {
+    pub min_size_bytes: usize,
+    /// Content-type prefixes skipped even if they clear `min_size_bytes`,
+    /// e.g. already-compressed media types. Data rather than a constant so
+    /// it can be overridden per gateway or per service.
+    pub skip_content_type_prefixes: Vec<String>,
+    /// Upper bound on how much of a response body is buffered for
+    /// compression/decompression; see [`DEFAULT_MAX_BUFFER_BYTES`].
+    pub max_buffer_bytes: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            min_size_bytes: DEFAULT_MIN_COMPRESS_BYTES,
+            skip_content_type_prefixes: DEFAULT_SKIP_CONTENT_TYPE_PREFIXES
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            max_buffer_bytes: DEFAULT_MAX_BUFFER_BYTES,
+        }
+    }
+}
+
+/// Per-service overrides for compression behavior, keyed by service name.
+/// Services with no entry get the gateway-wide default.
+pub struct CompressionConfigRegistry {
+    default: CompressionConfig,
+    per_service: HashMap<EncoreName, CompressionConfig>,
+}
+
+impl CompressionConfigRegistry {
+    pub fn new(default: CompressionConfig) -> Self {
+        Self {
+            default,
+            per_service: HashMap::new(),
+        }
+    }
+
+    pub fn set(&mut self, service: EncoreName, config: CompressionConfig) {
+        self.per_service.insert(service, config);
+    }
+
+    pub fn resolve(&self, service: &EncoreName) -> &CompressionConfig {
+        self.per_service.get(service).unwrap_or(&self.default)
+    }
+}
+
+/// Picks the best encoding the downstream client advertised via
+/// `Accept-Encoding`, preferring the most space-efficient codec we support.
+pub fn negotiate_encoding(req: &RequestHeader) -> Option<Encoding> {
+    let header = req.headers.get(ACCEPT_ENCODING)?.to_str().ok()?;
+
+    let mut accepts = |name: &str| {
+        header.split(',').any(|part| {
+            let mut segments = part.trim().splitn(2, ';');
+            let coding = segments.next().unwrap_or("").trim();
+            if !coding.eq_ignore_ascii_case(name) {
+                return false;
+            }
+            // `q=0` explicitly disallows this coding.
+            !matches!(segments.next(), Some(q) if q.trim().eq_ignore_ascii_case("q=0"))
+        })
+    };
+
+    if accepts("zstd") {
+        Some(Encoding::Zstd)
+    } else if accepts("br") {
+        Some(Encoding::Brotli)
+    } else if accepts("gzip") {
+        Some(Encoding::Gzip)
+    } else if accepts("deflate") {
+        Some(Encoding::Deflate)
+    } else {
+        None
+    }
+}
+
+/// Returns the encoding already applied to an upstream response body, if
+/// any of the codecs we know how to decode.
+pub fn response_encoding(resp: &ResponseHeader) -> Option<Encoding> {
+    let value = resp.headers.get(CONTENT_ENCODING)?.to_str().ok()?;
+    match value.trim() {
+        "gzip" => Some(Encoding::Gzip),
+        "br" => Some(Encoding::Brotli),
+        "zstd" => Some(Encoding::Zstd),
+        "deflate" => Some(Encoding::Deflate),
+        _ => None,
+    }
+}
+
+/// Whether a response is eligible for (re-)compression: large enough to be
+/// worth it, and not an already-compressed media type.
+pub fn is_compressible(resp: &ResponseHeader, config: &CompressionConfig) -> bool {
+    let content_type = resp
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    if config
+        .skip_content_type_prefixes
+        .iter()
+        .any(|prefix| content_type.starts_with(prefix.as_str()))
+    {
+        return false;
+    }
+
+    let content_length = resp
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+
+    // Unknown length (e.g. chunked/streamed) can't be compared against
+    // `min_size_bytes` here, and by the time the real size is known (once
+    // the body is fully buffered) the headers committing to a
+    // `Content-Encoding` have already been sent — so treat unknown length
+    // as not meeting the threshold rather than committing to compress a
+    // body that may turn out to be tiny.
+    content_length.is_some_and(|len| len >= config.min_size_bytes)
+}
+
+/// Updates response headers for a body we're about to compress to
+/// `encoding`: sets `Content-Encoding`, appends `Accept-Encoding` to `Vary`
+/// so caches key on it correctly, and drops `Content-Length` since the
+/// compressed size isn't known until the whole upstream body is read (the
+/// transformed body is sent chunked instead).
+pub fn apply_encoding_headers(resp: &mut ResponseHeader, encoding: Encoding) -> pingora::Result<()> {
+    resp.insert_header(CONTENT_ENCODING, encoding.as_str())?;
+    append_vary(resp, "Accept-Encoding")?;
+    drop_content_length(resp);
+    Ok(())
+}
+
+/// Clears `Content-Encoding`/`Content-Length` ahead of transparently
+/// decoding a body for a client that doesn't support the upstream's
+/// encoding.
+pub fn clear_encoding_headers(resp: &mut ResponseHeader) {
+    resp.remove_header(&CONTENT_ENCODING);
+    drop_content_length(resp);
+}
+
+/// What to do to a response body once it's fully buffered.
+#[derive(Clone, Copy, Debug)]
+pub enum BodyTransform {
+    /// Compress with the given encoding for a client that accepts it but an
+    /// upstream that sent plain bytes.
+    Compress(Encoding),
+    /// Decompress an upstream-encoded body for a client that didn't
+    /// advertise support for that encoding.
+    Decompress(Encoding),
+}
+
+/// Removes `Content-Length` ahead of a body transform whose output size
+/// isn't known until the whole upstream body has been read; the proxy
+/// falls back to chunked/close-delimited framing for the transformed body.
+pub fn drop_content_length(resp: &mut ResponseHeader) {
+    resp.remove_header(&CONTENT_LENGTH);
+}
+
+pub fn compress(encoding: Encoding, input: &[u8]) -> std::io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Gzip => {
+            let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            enc.write_all(input)?;
+            enc.finish()
+        }
+        Encoding::Brotli => {
+            let mut out = Vec::new();
+            brotli::BrotliCompress(
+                &mut std::io::Cursor::new(input),
+                &mut out,
+                &brotli::enc::BrotliEncoderParams::default(),
+            )?;
+            Ok(out)
+        }
+        Encoding::Zstd => zstd::stream::encode_all(input, 0),
+        Encoding::Deflate => {
+            let mut enc = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+            enc.write_all(input)?;
+            enc.finish()
+        }
+    }
+}
+
+pub fn decompress(encoding: Encoding, input: &[u8]) -> std::io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Gzip => {
+            let mut out = Vec::new();
+            flate2::read::GzDecoder::new(input).read_to_end(&mut out)?;
+            Ok(out)
+        }
+        Encoding::Brotli => {
+            let mut out = Vec::new();
+            brotli::BrotliDecompress(&mut std::io::Cursor::new(input), &mut out)?;
+            Ok(out)
+        }
+        Encoding::Zstd => zstd::stream::decode_all(input),
+        Encoding::Deflate => {
+            let mut out = Vec::new();
+            flate2::read::DeflateDecoder::new(input).read_to_end(&mut out)?;
+            Ok(out)
+        }
+    }
+}
+
+fn append_vary(resp: &mut ResponseHeader, value: &str) -> pingora::Result<()> {
+    let already_present = resp
+        .headers
+        .get(VARY)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|existing| existing.split(',').any(|v| v.trim().eq_ignore_ascii_case(value)));
+
+    if already_present {
+        return Ok(());
+    }
+
+    let new_value = match resp.headers.get(VARY).and_then(|v| v.to_str().ok()) {
+        Some(existing) if !existing.is_empty() => format!("{existing}, {value}"),
+        _ => value.to_string(),
+    };
+    resp.insert_header(VARY, new_value)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora::http::RequestHeader;
+
+    fn req_with_accept_encoding(value: &str) -> RequestHeader {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header(ACCEPT_ENCODING, value).unwrap();
+        req
+    }
+
+    #[test]
+    fn negotiate_encoding_prefers_zstd_then_brotli_then_gzip_then_deflate() {
+        assert_eq!(
+            negotiate_encoding(&req_with_accept_encoding("gzip, br, zstd, deflate")),
+            Some(Encoding::Zstd)
+        );
+        assert_eq!(
+            negotiate_encoding(&req_with_accept_encoding("gzip, br")),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(
+            negotiate_encoding(&req_with_accept_encoding("deflate, gzip")),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            negotiate_encoding(&req_with_accept_encoding("deflate")),
+            Some(Encoding::Deflate)
+        );
+    }
+
+    #[test]
+    fn negotiate_encoding_honors_q_zero() {
+        assert_eq!(
+            negotiate_encoding(&req_with_accept_encoding("zstd;q=0, gzip")),
+            Some(Encoding::Gzip)
+        );
+    }
+
+    #[test]
+    fn negotiate_encoding_none_without_header_or_match() {
+        let req = RequestHeader::build("GET", b"/", None).unwrap();
+        assert_eq!(negotiate_encoding(&req), None);
+        assert_eq!(negotiate_encoding(&req_with_accept_encoding("identity")), None);
+    }
+
+    #[test]
+    fn response_encoding_parses_known_content_encodings() {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header(CONTENT_ENCODING, "br").unwrap();
+        assert_eq!(response_encoding(&resp), Some(Encoding::Brotli));
+    }
+
+    #[test]
+    fn response_encoding_none_for_unknown_or_missing() {
+        let resp = ResponseHeader::build(200, None).unwrap();
+        assert_eq!(response_encoding(&resp), None);
+
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header(CONTENT_ENCODING, "compress").unwrap();
+        assert_eq!(response_encoding(&resp), None);
+    }
+
+    #[test]
+    fn is_compressible_rejects_skipped_content_types() {
+        let config = CompressionConfig::default();
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header(CONTENT_TYPE, "image/png").unwrap();
+        resp.insert_header(CONTENT_LENGTH, "10000").unwrap();
+        assert!(!is_compressible(&resp, &config));
+    }
+
+    #[test]
+    fn is_compressible_rejects_below_min_size() {
+        let config = CompressionConfig::default();
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header(CONTENT_TYPE, "text/plain").unwrap();
+        resp.insert_header(CONTENT_LENGTH, "10").unwrap();
+        assert!(!is_compressible(&resp, &config));
+    }
+
+    #[test]
+    fn is_compressible_rejects_unknown_length() {
+        // Unknown length can't be compared to `min_size_bytes` at header
+        // time, and headers are already committed by the time the real
+        // size is known, so it's treated as not meeting the threshold.
+        let config = CompressionConfig::default();
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header(CONTENT_TYPE, "text/plain").unwrap();
+        assert!(!is_compressible(&resp, &config));
+    }
+
+    #[test]
+    fn is_compressible_accepts_large_plain_body() {
+        let config = CompressionConfig::default();
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header(CONTENT_TYPE, "text/plain").unwrap();
+        resp.insert_header(CONTENT_LENGTH, "10000").unwrap();
+        assert!(is_compressible(&resp, &config));
+    }
+
+    #[test]
+    fn compress_decompress_round_trips_for_every_encoding() {
+        let input = b"the quick brown fox jumps over the lazy dog".repeat(10);
+        for encoding in [
+            Encoding::Gzip,
+            Encoding::Brotli,
+            Encoding::Zstd,
+            Encoding::Deflate,
+        ] {
+            let compressed = compress(encoding, &input).unwrap();
+            let decompressed = decompress(encoding, &compressed).unwrap();
+            assert_eq!(decompressed, input, "round trip failed for {encoding:?}");
+        }
+    }
+
+    #[test]
+    fn compression_config_registry_falls_back_to_default() {
+        let mut registry = CompressionConfigRegistry::new(CompressionConfig::default());
+        let overridden = CompressionConfig {
+            min_size_bytes: 1,
+            ..CompressionConfig::default()
+        };
+        registry.set(EncoreName::from("svc-a"), overridden.clone());
+
+        assert_eq!(
+            registry.resolve(&EncoreName::from("svc-a")).min_size_bytes,
+            1
+        );
+        assert_eq!(
+            registry.resolve(&EncoreName::from("svc-b")).min_size_bytes,
+            DEFAULT_MIN_COMPRESS_BYTES
+        );
+    }
+}
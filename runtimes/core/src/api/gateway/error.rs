@@ -0,0 +1,178 @@
+use serde::Serialize;
+
+use crate::EncoreName;
+
+/// The distinct ways the gateway's own routing/proxying logic can fail,
+/// as opposed to `api::Error`, which describes a failure a service
+/// handler itself returned.
+#[derive(Debug, thiserror::Error)]
+pub enum GatewayErrorKind {
+    #[error("no route matches {method} {path}")]
+    RouteNotFound { method: String, path: String },
+    #[error("couldn't resolve an upstream address")]
+    UpstreamUnresolvable,
+    #[error("upstream timed out")]
+    UpstreamTimeout,
+    #[error("authentication failed: {reason}")]
+    AuthFailed { reason: String },
+    #[error("couldn't decode websocket auth protocol: {reason}")]
+    WebSocketAuthDecode { reason: String },
+}
+
+impl GatewayErrorKind {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::RouteNotFound { .. } => "route_not_found",
+            Self::UpstreamUnresolvable { .. } => "upstream_unresolvable",
+            Self::UpstreamTimeout { .. } => "upstream_timeout",
+            Self::AuthFailed { .. } => "auth_failed",
+            Self::WebSocketAuthDecode { .. } => "websocket_auth_decode",
+        }
+    }
+
+    fn status_code(&self) -> u16 {
+        match self {
+            Self::RouteNotFound { .. } => 404,
+            Self::UpstreamUnresolvable { .. } => 502,
+            Self::UpstreamTimeout { .. } => 504,
+            Self::AuthFailed { .. } => 401,
+            Self::WebSocketAuthDecode { .. } => 400,
+        }
+    }
+}
+
+/// A gateway-level error, carrying enough context (the affected service,
+/// and the request's trace id when one had already been parsed) to render
+/// a consistent JSON problem body instead of an opaque 502/400.
+#[derive(Debug, thiserror::Error)]
+#[error("{kind}")]
+pub struct GatewayError {
+    pub kind: GatewayErrorKind,
+    pub service: Option<EncoreName>,
+    pub trace_id: Option<String>,
+}
+
+impl GatewayError {
+    pub fn new(kind: GatewayErrorKind) -> Self {
+        Self {
+            kind,
+            service: None,
+            trace_id: None,
+        }
+    }
+
+    pub fn with_service(mut self, service: EncoreName) -> Self {
+        self.service = Some(service);
+        self
+    }
+
+    pub fn with_trace_id(mut self, trace_id: impl Into<String>) -> Self {
+        self.trace_id = Some(trace_id.into());
+        self
+    }
+
+    pub fn status_code(&self) -> u16 {
+        self.kind.status_code()
+    }
+
+    /// Serializes this error as the JSON problem body sent to the client.
+    pub fn to_body(&self) -> Vec<u8> {
+        #[derive(Serialize)]
+        struct Body<'a> {
+            code: &'static str,
+            message: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            service: Option<&'a EncoreName>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            trace_id: Option<&'a str>,
+        }
+
+        let body = Body {
+            code: self.kind.code(),
+            message: self.kind.to_string(),
+            service: self.service.as_ref(),
+            trace_id: self.trace_id.as_deref(),
+        };
+
+        // the fields above are all infallible to serialize
+        serde_json::to_vec(&body).expect("GatewayError body is always serializable")
+    }
+}
+
+impl From<GatewayError> for Box<pingora::Error> {
+    fn from(err: GatewayError) -> Self {
+        let status = err.status_code();
+        pingora::Error::because(pingora::ErrorType::HTTPStatus(status), err.to_string(), err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_code_matches_kind() {
+        assert_eq!(
+            GatewayError::new(GatewayErrorKind::RouteNotFound {
+                method: "GET".to_string(),
+                path: "/missing".to_string(),
+            })
+            .status_code(),
+            404
+        );
+        assert_eq!(
+            GatewayError::new(GatewayErrorKind::UpstreamUnresolvable).status_code(),
+            502
+        );
+        assert_eq!(
+            GatewayError::new(GatewayErrorKind::UpstreamTimeout).status_code(),
+            504
+        );
+        assert_eq!(
+            GatewayError::new(GatewayErrorKind::AuthFailed {
+                reason: "bad token".to_string(),
+            })
+            .status_code(),
+            401
+        );
+        assert_eq!(
+            GatewayError::new(GatewayErrorKind::WebSocketAuthDecode {
+                reason: "bad header".to_string(),
+            })
+            .status_code(),
+            400
+        );
+    }
+
+    #[test]
+    fn to_body_includes_code_message_and_optional_context() {
+        let err = GatewayError::new(GatewayErrorKind::UpstreamTimeout)
+            .with_service(EncoreName::from("svc-a"))
+            .with_trace_id("trace-123");
+
+        let body: serde_json::Value = serde_json::from_slice(&err.to_body()).unwrap();
+        assert_eq!(body["code"], "upstream_timeout");
+        assert_eq!(body["message"], "upstream timed out");
+        assert_eq!(body["service"], "svc-a");
+        assert_eq!(body["trace_id"], "trace-123");
+    }
+
+    #[test]
+    fn to_body_omits_absent_optional_context() {
+        let err = GatewayError::new(GatewayErrorKind::UpstreamUnresolvable);
+        let body: serde_json::Value = serde_json::from_slice(&err.to_body()).unwrap();
+
+        assert!(!body.as_object().unwrap().contains_key("service"));
+        assert!(!body.as_object().unwrap().contains_key("trace_id"));
+    }
+
+    #[test]
+    fn builder_methods_set_service_and_trace_id() {
+        let err = GatewayError::new(GatewayErrorKind::UpstreamTimeout)
+            .with_service(EncoreName::from("svc-a"))
+            .with_trace_id("trace-123");
+
+        assert_eq!(err.service.as_ref().unwrap().to_string(), "svc-a");
+        assert_eq!(err.trace_id.as_deref(), Some("trace-123"));
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use crate::EncoreName;
+
+/// How a service's resolved upstream addresses are chosen between.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LoadBalancePolicy {
+    #[default]
+    RoundRobin,
+    LeastConnections,
+}
+
+#[derive(Clone, Debug)]
+pub struct HealthCheckConfig {
+    /// Consecutive failures (connect errors or 5xx) before an address is
+    /// ejected from rotation.
+    pub failure_threshold: u32,
+    /// How long an ejected address sits out before it's eligible again.
+    pub cooldown: Duration,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+struct AddrState {
+    addr: SocketAddr,
+    consecutive_failures: AtomicU32,
+    ejected_until: RwLock<Option<Instant>>,
+    in_flight: AtomicUsize,
+}
+
+impl AddrState {
+    fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            consecutive_failures: AtomicU32::new(0),
+            ejected_until: RwLock::new(None),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    fn is_healthy(&self) -> bool {
+        match *self.ejected_until.read().unwrap() {
+            Some(until) => Instant::now() >= until,
+            None => true,
+        }
+    }
+}
+
+/// Tracks the resolved addresses for one service and picks among the
+/// currently-healthy ones according to the configured load-balance policy.
+pub struct ServiceAddrs {
+    addrs: Vec<AddrState>,
+    policy: LoadBalancePolicy,
+    health: HealthCheckConfig,
+    next: AtomicUsize,
+    resolved_at: Instant,
+}
+
+impl ServiceAddrs {
+    pub fn new(addrs: Vec<SocketAddr>, policy: LoadBalancePolicy, health: HealthCheckConfig) -> Self {
+        Self {
+            addrs: addrs.into_iter().map(AddrState::new).collect(),
+            policy,
+            health,
+            next: AtomicUsize::new(0),
+            resolved_at: Instant::now(),
+        }
+    }
+
+    fn is_stale(&self, ttl: Duration) -> bool {
+        self.resolved_at.elapsed() >= ttl
+    }
+
+    /// Replaces the address set with a freshly-resolved one, carrying over
+    /// health/in-flight state for addresses that are still present instead
+    /// of resetting it, so a routine re-resolve doesn't un-eject an address
+    /// that's still failing or forget an address's current load.
+    fn refresh(&mut self, addrs: Vec<SocketAddr>) {
+        let mut previous: HashMap<SocketAddr, AddrState> = std::mem::take(&mut self.addrs)
+            .into_iter()
+            .map(|state| (state.addr, state))
+            .collect();
+
+        self.addrs = addrs
+            .into_iter()
+            .map(|addr| previous.remove(&addr).unwrap_or_else(|| AddrState::new(addr)))
+            .collect();
+        self.resolved_at = Instant::now();
+    }
+
+    /// Picks the next address to dial. Falls back to the full address set
+    /// (ignoring health) if every address is currently ejected, so a total
+    /// outage doesn't wedge the gateway shut.
+    pub fn pick(&self) -> Option<SocketAddr> {
+        let healthy: Vec<&AddrState> = self.addrs.iter().filter(|a| a.is_healthy()).collect();
+        let candidates = if healthy.is_empty() {
+            self.addrs.iter().collect::<Vec<_>>()
+        } else {
+            healthy
+        };
+
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let chosen = match self.policy {
+            LoadBalancePolicy::RoundRobin => {
+                let i = self.next.fetch_add(1, Ordering::Relaxed) % candidates.len();
+                candidates[i]
+            }
+            LoadBalancePolicy::LeastConnections => candidates
+                .into_iter()
+                .min_by_key(|a| a.in_flight.load(Ordering::Relaxed))
+                .expect("candidates is non-empty"),
+        };
+
+        chosen.in_flight.fetch_add(1, Ordering::Relaxed);
+        Some(chosen.addr)
+    }
+
+    /// Releases the in-flight slot acquired by `pick` once the request to
+    /// `addr` has completed (successfully or not).
+    pub fn release(&self, addr: SocketAddr) {
+        if let Some(state) = self.addrs.iter().find(|a| a.addr == addr) {
+            state.in_flight.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records a connection/5xx failure against `addr`, ejecting it from
+    /// rotation once it crosses the configured threshold.
+    pub fn record_failure(&self, addr: SocketAddr) {
+        let Some(state) = self.addrs.iter().find(|a| a.addr == addr) else {
+            return;
+        };
+
+        let failures = state.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= self.health.failure_threshold {
+            *state.ejected_until.write().unwrap() = Some(Instant::now() + self.health.cooldown);
+        }
+    }
+
+    /// Clears the failure count for `addr` after a successful request,
+    /// letting it rejoin rotation immediately rather than waiting out a
+    /// cooldown it's no longer earning.
+    pub fn record_success(&self, addr: SocketAddr) {
+        if let Some(state) = self.addrs.iter().find(|a| a.addr == addr) {
+            state.consecutive_failures.store(0, Ordering::Relaxed);
+            *state.ejected_until.write().unwrap() = None;
+        }
+    }
+}
+
+/// How long a service's resolved addresses are trusted before `pick` forces
+/// a fresh resolution. The baseline resolved on every request; this is the
+/// closest equivalent that still lets address state (health, in-flight
+/// counts) persist across requests in between.
+const DEFAULT_RESOLVE_TTL: Duration = Duration::from_secs(30);
+
+/// Per-service address pools, keyed by service name, shared across
+/// requests so health state survives from one request to the next.
+pub struct LoadBalancer {
+    services: RwLock<HashMap<EncoreName, ServiceAddrs>>,
+    policy: LoadBalancePolicy,
+    health: HealthCheckConfig,
+    resolve_ttl: Duration,
+}
+
+impl Default for LoadBalancer {
+    fn default() -> Self {
+        Self::new(LoadBalancePolicy::default(), HealthCheckConfig::default())
+    }
+}
+
+impl LoadBalancer {
+    pub fn new(policy: LoadBalancePolicy, health: HealthCheckConfig) -> Self {
+        Self {
+            services: RwLock::new(HashMap::new()),
+            policy,
+            health,
+            resolve_ttl: DEFAULT_RESOLVE_TTL,
+        }
+    }
+
+    /// Overrides the default TTL after which `pick` re-resolves a service's
+    /// addresses instead of reusing the cached set.
+    pub fn with_resolve_ttl(mut self, resolve_ttl: Duration) -> Self {
+        self.resolve_ttl = resolve_ttl;
+        self
+    }
+
+    /// Picks an address for `service`, (re-)resolving the address set via
+    /// `resolve` if this is the first time we've seen it or the cached set
+    /// has gone stale (see `resolve_ttl`) — so address changes (rolling
+    /// deploys, autoscaling) are eventually picked up rather than cached
+    /// forever, while health/in-flight state still persists across requests
+    /// in between.
+    pub fn pick(
+        &self,
+        service: &EncoreName,
+        resolve: impl FnOnce() -> pingora::Result<Vec<SocketAddr>>,
+    ) -> pingora::Result<SocketAddr> {
+        {
+            let services = self.services.read().unwrap();
+            if let Some(existing) = services.get(service) {
+                if !existing.is_stale(self.resolve_ttl) {
+                    if let Some(addr) = existing.pick() {
+                        return Ok(addr);
+                    }
+                }
+            }
+        }
+
+        let addrs = resolve()?;
+        let mut services = self.services.write().unwrap();
+        match services.get_mut(service) {
+            Some(existing) => existing.refresh(addrs),
+            None => {
+                services.insert(
+                    service.clone(),
+                    ServiceAddrs::new(addrs, self.policy, self.health.clone()),
+                );
+            }
+        }
+
+        services.get(service).and_then(ServiceAddrs::pick).ok_or_else(|| {
+            pingora::Error::explain(
+                pingora::ErrorType::InternalError,
+                "didn't find any healthy upstream addresses",
+            )
+        })
+    }
+
+    pub fn record_failure(&self, service: &EncoreName, addr: SocketAddr) {
+        if let Some(s) = self.services.read().unwrap().get(service) {
+            s.record_failure(addr);
+        }
+    }
+
+    pub fn record_success(&self, service: &EncoreName, addr: SocketAddr) {
+        if let Some(s) = self.services.read().unwrap().get(service) {
+            s.record_success(addr);
+        }
+    }
+
+    pub fn release(&self, service: &EncoreName, addr: SocketAddr) {
+        if let Some(s) = self.services.read().unwrap().get(service) {
+            s.release(addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn round_robin_cycles_through_all_addresses() {
+        let addrs = ServiceAddrs::new(
+            vec![addr(1), addr(2), addr(3)],
+            LoadBalancePolicy::RoundRobin,
+            HealthCheckConfig::default(),
+        );
+
+        let picked: Vec<SocketAddr> = (0..3).map(|_| addrs.pick().unwrap()).collect();
+        assert_eq!(picked, vec![addr(1), addr(2), addr(3)]);
+    }
+
+    #[test]
+    fn least_connections_prefers_the_least_loaded_address() {
+        let addrs = ServiceAddrs::new(
+            vec![addr(1), addr(2)],
+            LoadBalancePolicy::LeastConnections,
+            HealthCheckConfig::default(),
+        );
+
+        // Load addr(1) up, so addr(2) wins the next pick; once both carry
+        // one in-flight request the tie goes to the first address.
+        assert_eq!(addrs.pick(), Some(addr(1)));
+        assert_eq!(addrs.pick(), Some(addr(2)));
+        assert_eq!(addrs.pick(), Some(addr(1)));
+    }
+
+    #[test]
+    fn record_failure_ejects_after_threshold_and_release_frees_the_slot() {
+        let health = HealthCheckConfig {
+            failure_threshold: 2,
+            cooldown: Duration::from_secs(60),
+        };
+        let addrs = ServiceAddrs::new(
+            vec![addr(1), addr(2)],
+            LoadBalancePolicy::RoundRobin,
+            health,
+        );
+
+        addrs.record_failure(addr(1));
+        addrs.record_failure(addr(1));
+
+        // addr(1) is now ejected, so every pick lands on addr(2).
+        for _ in 0..4 {
+            assert_eq!(addrs.pick(), Some(addr(2)));
+        }
+
+        // Four picks hold four in-flight slots on addr(2); release frees one.
+        addrs.release(addr(2));
+        assert_eq!(addrs.addrs[1].in_flight.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn all_addresses_ejected_falls_back_to_full_set() {
+        let health = HealthCheckConfig {
+            failure_threshold: 1,
+            cooldown: Duration::from_secs(60),
+        };
+        let addrs = ServiceAddrs::new(vec![addr(1), addr(2)], LoadBalancePolicy::RoundRobin, health);
+
+        addrs.record_failure(addr(1));
+        addrs.record_failure(addr(2));
+
+        // Everything's ejected, but `pick` still returns something rather
+        // than leaving the gateway with no upstream at all.
+        assert!(addrs.pick().is_some());
+    }
+
+    #[test]
+    fn record_success_clears_failures_and_ejection() {
+        let health = HealthCheckConfig {
+            failure_threshold: 1,
+            cooldown: Duration::from_secs(60),
+        };
+        let addrs = ServiceAddrs::new(vec![addr(1), addr(2)], LoadBalancePolicy::RoundRobin, health);
+
+        addrs.record_failure(addr(1));
+        addrs.record_success(addr(1));
+
+        // addr(1) should be back in rotation immediately.
+        let picked: Vec<SocketAddr> = (0..2).map(|_| addrs.pick().unwrap()).collect();
+        assert!(picked.contains(&addr(1)));
+    }
+
+    #[test]
+    fn load_balancer_reuses_cached_addresses_within_ttl() {
+        let lb = LoadBalancer::new(LoadBalancePolicy::RoundRobin, HealthCheckConfig::default())
+            .with_resolve_ttl(Duration::from_secs(60));
+        let service = EncoreName::from("svc-a");
+
+        lb.pick(&service, || Ok(vec![addr(1)])).unwrap();
+        let resolved_again = std::cell::Cell::new(false);
+        lb.pick(&service, || {
+            resolved_again.set(true);
+            Ok(vec![addr(2)])
+        })
+        .unwrap();
+
+        assert!(!resolved_again.get());
+    }
+
+    #[test]
+    fn load_balancer_re_resolves_once_stale_and_preserves_health_state() {
+        let lb = LoadBalancer::new(LoadBalancePolicy::RoundRobin, HealthCheckConfig::default())
+            .with_resolve_ttl(Duration::from_millis(1));
+        let service = EncoreName::from("svc-a");
+
+        let picked = lb.pick(&service, || Ok(vec![addr(1), addr(2)])).unwrap();
+        lb.record_failure(&service, picked);
+        lb.record_failure(&service, picked);
+        lb.record_failure(&service, picked);
+
+        std::thread::sleep(Duration::from_millis(5));
+
+        // Re-resolving with the same address set should still avoid the
+        // address that was just ejected, proving health state survived the
+        // refresh instead of getting reset.
+        for _ in 0..4 {
+            let next = lb.pick(&service, || Ok(vec![addr(1), addr(2)])).unwrap();
+            assert_ne!(next, picked);
+        }
+    }
+}
@@ -1,9 +1,16 @@
+mod cache;
+mod compression;
+mod error;
+mod lb;
 mod router;
+mod timeout;
+mod tls;
 
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::Context;
 use axum::async_trait;
@@ -12,6 +19,7 @@ use base64::Engine;
 use bytes::{BufMut, Bytes, BytesMut};
 use http::header::SEC_WEBSOCKET_PROTOCOL;
 use hyper::header;
+use pingora::cache::{CacheKey, RespCacheable};
 use pingora::http::{RequestHeader, ResponseHeader};
 use pingora::protocols::http::error_resp;
 use pingora::proxy::{http_proxy_service, ProxyHttp, Session};
@@ -34,6 +42,13 @@ use crate::{api, model, EncoreName};
 use super::cors::cors_headers_config::CorsHeadersConfig;
 use super::encore_routes::healthz;
 
+pub use cache::{CacheConfig, GatewayCache};
+pub use compression::{CompressionConfig, CompressionConfigRegistry};
+pub use error::{GatewayError, GatewayErrorKind};
+pub use lb::{HealthCheckConfig, LoadBalancePolicy};
+pub use timeout::{TimeoutConfig, TimeoutConfigRegistry};
+pub use tls::{TlsConfigRegistry, UpstreamTlsConfig};
+
 #[derive(Clone)]
 pub struct Gateway {
     inner: Arc<Inner>,
@@ -46,12 +61,36 @@ struct Inner {
     cors_config: CorsHeadersConfig,
     healthz: healthz::Handler,
     own_api_address: Option<SocketAddr>,
+    cache: Option<&'static GatewayCache>,
+    load_balancer: lb::LoadBalancer,
+    timeout_config: timeout::TimeoutConfigRegistry,
+    tls_config: tls::TlsConfigRegistry,
+    compression_config: compression::CompressionConfigRegistry,
 }
 
 pub struct GatewayCtx {
     upstream_service_name: EncoreName,
     upstream_base_path: String,
     upstream_host: Option<String>,
+    body_transform: Option<compression::BodyTransform>,
+    body_buffer: BytesMut,
+    selected_addr: SocketAddr,
+    /// Whether this attempt's `selected_addr` has already had its
+    /// load-balancer bookkeeping (failure/success count, in-flight slot)
+    /// settled by `error_while_proxy`. Checked by `fail_to_proxy`/`logging`
+    /// so a retried attempt's address isn't double-counted once the
+    /// request ultimately finishes.
+    addr_settled: bool,
+    timeout_config: timeout::TimeoutConfig,
+    /// End-to-end deadline for the whole request, including any retries;
+    /// set once on the first attempt and carried forward across retries so
+    /// a hung backend can't be escaped indefinitely by re-dispatching.
+    deadline: Instant,
+    retry_state: timeout::RetryState,
+    /// Set once `upstream_request_filter` has parsed the request's
+    /// `CallMeta`; `None` for failures that happen before then (e.g.
+    /// route resolution or upstream connect failures).
+    trace_id: Option<String>,
 }
 
 impl GatewayCtx {
@@ -84,6 +123,12 @@ impl Gateway {
         cors_config: CorsHeadersConfig,
         healthz: healthz::Handler,
         own_api_address: Option<SocketAddr>,
+        cache: Option<GatewayCache>,
+        load_balance_policy: lb::LoadBalancePolicy,
+        health_check: lb::HealthCheckConfig,
+        timeout_config: timeout::TimeoutConfigRegistry,
+        tls_config: tls::TlsConfigRegistry,
+        compression_config: compression::CompressionConfigRegistry,
     ) -> anyhow::Result<Self> {
         let shared = Arc::new(SharedGatewayData {
             name,
@@ -98,6 +143,11 @@ impl Gateway {
             router.add_routes(svc, routes)?;
         }
 
+        // The cache's storage/eviction manager must be `'static` to satisfy
+        // pingora's cache APIs, so it's leaked for the lifetime of the
+        // process, same as any other gateway-wide singleton.
+        let cache = cache.map(|c| &*Box::leak(Box::new(c)));
+
         Ok(Gateway {
             inner: Arc::new(Inner {
                 shared,
@@ -106,6 +156,11 @@ impl Gateway {
                 cors_config,
                 healthz,
                 own_api_address,
+                cache,
+                load_balancer: lb::LoadBalancer::new(load_balance_policy, health_check),
+                timeout_config,
+                tls_config,
+                compression_config,
             }),
         })
     }
@@ -114,6 +169,30 @@ impl Gateway {
         self.inner.shared.auth.as_ref()
     }
 
+    /// Resolves the service a request routes to, independent of any
+    /// upstream-selection state (used by both the cache filters, which run
+    /// before `upstream_peer`, and `upstream_peer` itself).
+    fn route_to_service(&self, session: &Session) -> pingora::Result<EncoreName> {
+        let path = session.req_header().uri.path();
+        let method: Method = session
+            .req_header()
+            .method
+            .as_ref()
+            .try_into()
+            .map_err(|e| Error::because(ErrorType::HTTPStatus(400), "invalid http method", e))?;
+        self.inner
+            .router
+            .route_to_service(method, path)
+            .map(|s| s.clone())
+            .map_err(|_| {
+                error::GatewayError::new(error::GatewayErrorKind::RouteNotFound {
+                    method: session.req_header().method.to_string(),
+                    path: path.to_string(),
+                })
+                .into()
+            })
+    }
+
     pub async fn serve(self, listen_addr: &str) -> anyhow::Result<()> {
         let conf = Arc::new(
             ServerConf::new_with_opt_override(&Opt {
@@ -187,6 +266,84 @@ impl ProxyHttp for Gateway {
         Ok(false)
     }
 
+    async fn request_cache_filter(
+        &self,
+        session: &mut Session,
+        _ctx: &mut Self::CTX,
+    ) -> pingora::Result<()> {
+        let Some(cache) = self.inner.cache else {
+            return Ok(());
+        };
+
+        let method: Method = session
+            .req_header()
+            .method
+            .as_ref()
+            .try_into()
+            .map_err(|e| Error::because(ErrorType::HTTPStatus(400), "invalid http method", e))?;
+        let path = session.req_header().uri.path().to_string();
+
+        if let Ok(service_name) = self.route_to_service(session) {
+            if cache.is_enabled_for(&service_name, &method, &path) {
+                cache.enable(session);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn cache_key_callback(
+        &self,
+        session: &Session,
+        _ctx: &mut Self::CTX,
+    ) -> pingora::Result<CacheKey> {
+        let cache = self
+            .inner
+            .cache
+            .ok_or_else(|| Error::explain(ErrorType::InternalError, "cache not configured"))?;
+
+        let uri = &session.req_header().uri;
+        let path_and_query = uri.path_and_query().map_or(uri.path(), |pq| pq.as_str());
+        let method: Method = session
+            .req_header()
+            .method
+            .as_ref()
+            .try_into()
+            .map_err(|e| Error::because(ErrorType::HTTPStatus(400), "invalid http method", e))?;
+        let service_name = self.route_to_service(session)?;
+
+        Ok(cache.cache_key(&service_name, &method, path_and_query))
+    }
+
+    fn response_cache_filter(
+        &self,
+        session: &Session,
+        resp: &ResponseHeader,
+        _ctx: &mut Self::CTX,
+    ) -> pingora::Result<RespCacheable> {
+        let cache = self
+            .inner
+            .cache
+            .ok_or_else(|| Error::explain(ErrorType::InternalError, "cache not configured"))?;
+        let service_name = self.route_to_service(session)?;
+        let path = session.req_header().uri.path();
+
+        Ok(cache.resp_cacheable(&service_name, path, resp))
+    }
+
+    fn cache_vary_filter(
+        &self,
+        meta: &pingora::cache::CacheMeta,
+        _ctx: &mut Self::CTX,
+        req: &RequestHeader,
+    ) -> Option<HashMap<String, Option<String>>> {
+        let names = cache::vary_header_names(meta.response_header());
+        if names.is_empty() {
+            return None;
+        }
+        Some(cache::vary_values(req, &names))
+    }
+
     async fn upstream_peer(
         &self,
         session: &mut Session,
@@ -200,59 +357,149 @@ impl ProxyHttp for Gateway {
             }
         }
 
-        let method: Method = session
-            .req_header()
-            .method
-            .as_ref()
-            .try_into()
-            .map_err(|e| Error::because(ErrorType::HTTPStatus(400), "invalid http method", e))?;
+        // On a retry, `upstream_peer` runs again for the same request;
+        // carry the retry count and overall deadline forward instead of
+        // resetting them (the previous attempt's `selected_addr` is
+        // intentionally dropped here: `error_while_proxy` already settled
+        // its load-balancer accounting before this call).
+        let (retry_state, prior_deadline) = match ctx.take() {
+            Some(c) => (c.retry_state, Some(c.deadline)),
+            None => (timeout::RetryState::default(), None),
+        };
 
-        let service_name = self.inner.router.route_to_service(method, path)?;
+        let service_name = self.route_to_service(session)?;
 
         let upstream = self
             .inner
             .service_registry
-            .service_base_url(service_name)
-            .ok_or_else(|| Error::explain(ErrorType::InternalError, "couldn't find upstream"))?;
+            .service_base_url(&service_name)
+            .ok_or_else(|| {
+                error::GatewayError::new(error::GatewayErrorKind::UpstreamUnresolvable)
+                    .with_service(service_name.clone())
+            })?;
 
         let upstream_url: Url = upstream
             .parse()
             .map_err(|e| Error::because(ErrorType::InternalError, "upstream not a valid url", e))?;
 
-        let upstream_addrs = upstream_url
-            .socket_addrs(|| match upstream_url.scheme() {
-                "https" => Some(443),
-                "http" => Some(80),
-                _ => None,
-            })
-            .map_err(|e| {
-                Error::because(
-                    ErrorType::InternalError,
-                    "couldn't lookup upstream ip address",
-                    e,
-                )
-            })?;
+        let timeout_config = self.inner.timeout_config.resolve(&service_name);
+
+        // Checked before `pick()` deliberately: `pick()` increments the
+        // chosen address's in-flight counter, so an early return after it
+        // (e.g. on a retry that finally trips the deadline) would leak that
+        // slot forever, since `ctx` stays `None` and no later hook ever
+        // learns which address to release.
+        let deadline =
+            prior_deadline.unwrap_or_else(|| Instant::now() + timeout_config.total_timeout);
+        if Instant::now() >= deadline {
+            return Err(error::GatewayError::new(error::GatewayErrorKind::UpstreamTimeout)
+                .with_service(service_name)
+                .into());
+        }
 
-        let upstream_addr = upstream_addrs.first().ok_or_else(|| {
-            Error::explain(
-                ErrorType::InternalError,
-                "didn't find any upstream ip addresses",
-            )
+        let upstream_addr = self.inner.load_balancer.pick(&service_name, || {
+            upstream_url
+                .socket_addrs(|| match upstream_url.scheme() {
+                    "https" => Some(443),
+                    "http" => Some(80),
+                    _ => None,
+                })
+                .map_err(|e| {
+                    Error::because(
+                        ErrorType::InternalError,
+                        "couldn't lookup upstream ip address",
+                        e,
+                    )
+                })
         })?;
 
         let tls = upstream_url.scheme() == "https";
         let host = upstream_url.host().map(|h| h.to_string());
-        let peer = HttpPeer::new(upstream_addr, tls, host.clone().unwrap_or_default());
+        let mut peer = HttpPeer::new(&upstream_addr, tls, host.clone().unwrap_or_default());
+
+        if tls {
+            if let Some(tls_config) = self.inner.tls_config.get(&service_name) {
+                tls_config.apply(&mut peer);
+            }
+        }
+
+        timeout_config.apply(&mut peer);
 
         ctx.replace(GatewayCtx {
             upstream_base_path: upstream_url.path().to_string(),
             upstream_host: host,
             upstream_service_name: service_name.clone(),
+            body_transform: None,
+            body_buffer: BytesMut::new(),
+            selected_addr: upstream_addr,
+            addr_settled: false,
+            timeout_config,
+            deadline,
+            retry_state,
+            trace_id: None,
         });
 
         Ok(Box::new(peer))
     }
 
+    fn error_while_proxy(
+        &self,
+        peer: &HttpPeer,
+        session: &mut Session,
+        mut e: Box<Error>,
+        ctx: &mut Self::CTX,
+        client_reused: bool,
+    ) -> Box<Error> {
+        e = e.more_context(format!("upstream peer: {peer}"));
+
+        // This attempt's address is done, whether or not we go on to retry
+        // onto a different one: settle its load-balancer bookkeeping here,
+        // before the next `upstream_peer` call (if any) discards this ctx
+        // and picks a new address. Without this, a retried request would
+        // permanently leak the in-flight slot it acquired on the address it
+        // retried away from, and that address would never accrue the
+        // consecutive-failure count passive health-checking relies on.
+        if let Some(gateway_ctx) = ctx.as_mut() {
+            self.inner.load_balancer.record_failure(
+                &gateway_ctx.upstream_service_name,
+                gateway_ctx.selected_addr,
+            );
+            self.inner
+                .load_balancer
+                .release(&gateway_ctx.upstream_service_name, gateway_ctx.selected_addr);
+            gateway_ctx.addr_settled = true;
+        }
+
+        // Pingora already retries a request that failed on a reused
+        // keep-alive connection (the server may have just closed it from
+        // under us). On top of that, bounded-retry idempotent requests
+        // that failed to connect or were reset before any response bytes
+        // arrived, up to the route's configured retry count.
+        let extra_retry = ctx.as_mut().is_some_and(|gateway_ctx| {
+            let method: Option<Method> = session.req_header().method.as_ref().try_into().ok();
+            let retryable = method.is_some_and(|method| {
+                timeout::is_retryable(method, &session.req_header().headers)
+            });
+
+            if Instant::now() < gateway_ctx.deadline
+                && gateway_ctx
+                    .retry_state
+                    .should_retry(&gateway_ctx.timeout_config, retryable)
+            {
+                gateway_ctx.retry_state.attempts += 1;
+                true
+            } else {
+                false
+            }
+        });
+
+        if client_reused || extra_retry {
+            e.set_retry(true);
+        }
+
+        e
+    }
+
     async fn response_filter(
         &self,
         session: &mut Session,
@@ -262,15 +509,151 @@ impl ProxyHttp for Gateway {
     where
         Self::CTX: Send + Sync,
     {
-        if ctx.is_some() {
+        if let Some(gateway_ctx) = ctx.as_mut() {
+            gateway_ctx.retry_state.response_started = true;
+        }
+
+        let served_from_cache = session.cache.enabled() && session.cache.phase().is_hit();
+
+        // Cache hits never go through `upstream_peer`, so `ctx` is still
+        // empty; CORS headers still need to be applied to the served
+        // response either way.
+        if ctx.is_some() || served_from_cache {
             self.inner
                 .cors_config
                 .apply(session.req_header(), upstream_response)?;
         }
 
+        if session.cache.enabled() {
+            let value = if served_from_cache { "HIT" } else { "MISS" };
+            upstream_response.insert_header("X-Cache", value)?;
+        }
+
+        if let Some(gateway_ctx) = ctx.as_mut() {
+            if !session.is_upgrade_req() {
+                let upstream_encoding = compression::response_encoding(upstream_response);
+                let downstream_wants = compression::negotiate_encoding(session.req_header());
+
+                let compression_config = self
+                    .inner
+                    .compression_config
+                    .resolve(&gateway_ctx.upstream_service_name);
+
+                let downstream_accepts_upstream_enc = upstream_encoding.is_some_and(|enc| {
+                    session
+                        .req_header()
+                        .headers
+                        .get(header::ACCEPT_ENCODING)
+                        .and_then(|v| v.to_str().ok())
+                        .is_some_and(|accept| {
+                            accept.split(',').any(|part| {
+                                let mut segments = part.trim().splitn(2, ';');
+                                segments.next().unwrap_or("").trim().eq_ignore_ascii_case(enc.as_str())
+                                    && !matches!(segments.next(), Some(q) if q.trim().eq_ignore_ascii_case("q=0"))
+                            })
+                        })
+                });
+
+                gateway_ctx.body_transform = match upstream_encoding {
+                    None => downstream_wants
+                        .filter(|_| compression::is_compressible(upstream_response, compression_config))
+                        .map(compression::BodyTransform::Compress),
+                    Some(upstream_enc) if !downstream_accepts_upstream_enc => {
+                        Some(compression::BodyTransform::Decompress(upstream_enc))
+                    }
+                    Some(_) => None,
+                };
+
+                match gateway_ctx.body_transform {
+                    Some(compression::BodyTransform::Compress(enc)) => {
+                        compression::apply_encoding_headers(upstream_response, enc)?
+                    }
+                    Some(compression::BodyTransform::Decompress(_)) => {
+                        compression::clear_encoding_headers(upstream_response)
+                    }
+                    None => {}
+                }
+            }
+        }
+
         Ok(())
     }
 
+    fn response_body_filter(
+        &self,
+        _session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> pingora::Result<Option<std::time::Duration>> {
+        let Some(gateway_ctx) = ctx.as_mut() else {
+            return Ok(None);
+        };
+
+        // Bounds the whole response exchange, not just connection setup:
+        // checked as each body chunk arrives, so a backend that keeps
+        // trickling data can't run past the deadline. A backend that stops
+        // sending altogether is cut off by `read_timeout` instead.
+        if Instant::now() >= gateway_ctx.deadline {
+            return Err(error::GatewayError::new(error::GatewayErrorKind::UpstreamTimeout)
+                .with_service(gateway_ctx.upstream_service_name.clone())
+                .into());
+        }
+
+        let Some(transform) = gateway_ctx.body_transform else {
+            return Ok(None);
+        };
+
+        let compression_config = self
+            .inner
+            .compression_config
+            .resolve(&gateway_ctx.upstream_service_name)
+            .clone();
+
+        // Compression/decompression needs the whole body up front, so chunks
+        // are buffered here and only released once the upstream is done.
+        // One `Gateway` proxies every service, so this is bounded rather
+        // than left to grow without limit: a response past the configured
+        // cap aborts instead of risking the whole process's memory.
+        if let Some(chunk) = body.take() {
+            gateway_ctx.body_buffer.extend_from_slice(&chunk);
+        }
+
+        if gateway_ctx.body_buffer.len() > compression_config.max_buffer_bytes {
+            return Err(Error::explain(
+                ErrorType::InternalError,
+                format!(
+                    "response body exceeded the {}-byte buffering limit for compression",
+                    compression_config.max_buffer_bytes
+                ),
+            ));
+        }
+
+        if !end_of_stream {
+            return Ok(None);
+        }
+
+        let buffered = std::mem::take(&mut gateway_ctx.body_buffer).freeze();
+
+        // `Content-Encoding` was already sent in `response_filter`, so the
+        // body is transformed even if it turned out smaller than its
+        // declared length.
+        let transformed = match transform {
+            compression::BodyTransform::Compress(enc) => compression::compress(enc, &buffered),
+            compression::BodyTransform::Decompress(enc) => compression::decompress(enc, &buffered),
+        }
+        .map_err(|e| {
+            Error::because(
+                ErrorType::InternalError,
+                "failed to transform response body for compression",
+                e,
+            )
+        })?;
+
+        *body = Some(Bytes::from(transformed));
+        Ok(None)
+    }
+
     async fn upstream_request_filter(
         &self,
         session: &mut Session,
@@ -280,7 +663,7 @@ impl ProxyHttp for Gateway {
     where
         Self::CTX: Send + Sync,
     {
-        if let Some(gateway_ctx) = ctx.as_ref() {
+        if let Some(gateway_ctx) = ctx.as_mut() {
             let new_uri = gateway_ctx
                 .prepend_base_path(&upstream_request.uri)
                 .map_err(|e| {
@@ -302,11 +685,10 @@ impl ProxyHttp for Gateway {
 
             if session.is_upgrade_req() {
                 update_request_from_websocket_protocol(upstream_request).map_err(|e| {
-                    Error::because(
-                        ErrorType::UnknownError,
-                        "failed parsing websocket protocol header",
-                        e,
-                    )
+                    error::GatewayError::new(error::GatewayErrorKind::WebSocketAuthDecode {
+                        reason: e.to_string(),
+                    })
+                    .with_service(gateway_ctx.upstream_service_name.clone())
                 })?;
             }
 
@@ -328,6 +710,7 @@ impl ProxyHttp for Gateway {
             if call_meta.parent_span_id.is_none() {
                 call_meta.parent_span_id = Some(model::SpanId::generate());
             }
+            gateway_ctx.trace_id = Some(call_meta.trace_id.to_string());
 
             let caller = Caller::Gateway {
                 gateway: self.inner.shared.name.clone(),
@@ -352,7 +735,11 @@ impl ProxyHttp for Gateway {
                     .authenticate(upstream_request, call_meta.clone())
                     .await
                     .map_err(|e| {
-                        Error::because(ErrorType::InternalError, "couldn't authenticate request", e)
+                        error::GatewayError::new(error::GatewayErrorKind::AuthFailed {
+                            reason: e.to_string(),
+                        })
+                        .with_service(gateway_ctx.upstream_service_name.clone())
+                        .with_trace_id(call_meta.trace_id.to_string())
                     })?;
 
                 if let auth::AuthResponse::Authenticated {
@@ -373,35 +760,73 @@ impl ProxyHttp for Gateway {
         Ok(())
     }
 
-    async fn fail_to_proxy(&self, session: &mut Session, e: &Error, _ctx: &mut Self::CTX) -> u16
+    async fn fail_to_proxy(&self, session: &mut Session, e: &Error, ctx: &mut Self::CTX) -> u16
     where
         Self::CTX: Send + Sync,
     {
         // modified version of `Session::respond_error` that adds cors headers,
         // and handles specific errors
 
-        let code = match e.etype() {
-            ErrorType::HTTPStatus(code) => *code,
-            _ => {
-                match e.esource() {
+        if matches!(e.esource(), ErrorSource::Upstream) {
+            if let Some(gateway_ctx) = ctx.as_ref() {
+                // Connect-phase failures already had this recorded by
+                // `error_while_proxy`; this only needs to catch upstream
+                // errors that never went through a retry decision (e.g. a
+                // malformed response after a successful connect).
+                if !gateway_ctx.addr_settled {
+                    self.inner.load_balancer.record_failure(
+                        &gateway_ctx.upstream_service_name,
+                        gateway_ctx.selected_addr,
+                    );
+                }
+            }
+        }
+
+        // A connect/read/write timeout doesn't carry a `GatewayError` cause
+        // (it originates inside pingora, not our own code), so one is
+        // synthesized here from whatever route/trace context we have.
+        let synthetic_timeout = matches!(
+            e.etype(),
+            ErrorType::ConnectTimedout | ErrorType::ReadTimedout | ErrorType::WriteTimedout
+        )
+        .then(|| {
+            let mut err = error::GatewayError::new(error::GatewayErrorKind::UpstreamTimeout);
+            if let Some(gateway_ctx) = ctx.as_ref() {
+                err = err.with_service(gateway_ctx.upstream_service_name.clone());
+                if let Some(trace_id) = &gateway_ctx.trace_id {
+                    err = err.with_trace_id(trace_id.clone());
+                }
+            }
+            err
+        });
+
+        let gateway_error = synthetic_timeout.as_ref().or_else(|| as_gateway_error(e));
+
+        let code = if let Some(gw_err) = gateway_error {
+            gw_err.status_code()
+        } else {
+            match e.etype() {
+                ErrorType::HTTPStatus(code) => *code,
+                _ => match e.esource() {
                     ErrorSource::Upstream => 502,
-                    ErrorSource::Downstream => {
-                        match e.etype() {
-                            ErrorType::WriteError
-                            | ErrorType::ReadError
-                            | ErrorType::ConnectionClosed => {
-                                /* conn already dead */
-                                return 0;
-                            }
-                            _ => 400,
+                    ErrorSource::Downstream => match e.etype() {
+                        ErrorType::WriteError
+                        | ErrorType::ReadError
+                        | ErrorType::ConnectionClosed => {
+                            /* conn already dead */
+                            return 0;
                         }
-                    }
+                        _ => 400,
+                    },
                     ErrorSource::Internal | ErrorSource::Unset => 500,
-                }
+                },
             }
         };
 
-        let (mut resp, body) = if let Some(api_error) = as_api_error(e) {
+        let (mut resp, body) = if let Some(gw_err) = gateway_error {
+            let (resp, body) = gateway_error_response(gw_err);
+            (resp, Some(body))
+        } else if let Some(api_error) = as_api_error(e) {
             let (resp, body) = api_error_response(api_error);
             (resp, Some(body))
         } else {
@@ -440,6 +865,41 @@ impl ProxyHttp for Gateway {
 
         code
     }
+
+    async fn logging(&self, session: &mut Session, e: Option<&Error>, ctx: &mut Self::CTX)
+    where
+        Self::CTX: Send + Sync,
+    {
+        if let Some(gateway_ctx) = ctx.as_ref() {
+            // `fail_to_proxy`/`error_while_proxy` already recorded upstream
+            // failures and released the in-flight slot for an address
+            // that was settled earlier in this request's lifetime; this
+            // only needs to count an upstream 5xx as a failure, mark any
+            // other completed request as healthy, and release the slot for
+            // an address that never went through a retry decision.
+            if !gateway_ctx.addr_settled {
+                if e.is_none() {
+                    let upstream_5xx = session
+                        .response_written()
+                        .is_some_and(|resp| resp.status.is_server_error());
+                    if upstream_5xx {
+                        self.inner.load_balancer.record_failure(
+                            &gateway_ctx.upstream_service_name,
+                            gateway_ctx.selected_addr,
+                        );
+                    } else {
+                        self.inner.load_balancer.record_success(
+                            &gateway_ctx.upstream_service_name,
+                            gateway_ctx.selected_addr,
+                        );
+                    }
+                }
+                self.inner
+                    .load_balancer
+                    .release(&gateway_ctx.upstream_service_name, gateway_ctx.selected_addr);
+            }
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -501,6 +961,27 @@ fn as_api_error(err: &pingora::Error) -> Option<&api::Error> {
     }
 }
 
+fn as_gateway_error(err: &pingora::Error) -> Option<&error::GatewayError> {
+    err.cause
+        .as_ref()
+        .and_then(|cause| cause.downcast_ref::<error::GatewayError>())
+}
+
+fn gateway_error_response(err: &error::GatewayError) -> (ResponseHeader, bytes::Bytes) {
+    let body = err.to_body();
+
+    let mut resp = ResponseHeader::build(err.status_code(), Some(4)).unwrap();
+    resp.insert_header(header::SERVER, &pingora::protocols::http::SERVER_NAME[..])
+        .unwrap();
+    resp.insert_header(header::CONTENT_LENGTH, body.len()).unwrap();
+    resp.insert_header(header::CACHE_CONTROL, "private, no-store")
+        .unwrap();
+    resp.insert_header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+        .unwrap();
+
+    (resp, body.into())
+}
+
 fn api_error_response(err: &api::Error) -> (ResponseHeader, bytes::Bytes) {
     let mut buf = BytesMut::with_capacity(128).writer();
     serde_json::to_writer(&mut buf, &err).unwrap();
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use pingora::upstreams::peer::HttpPeer;
+
+use crate::api::schema::Method;
+use crate::EncoreName;
+
+/// Per-route upstream timeouts and retry budget.
+#[derive(Clone, Debug)]
+pub struct TimeoutConfig {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub total_timeout: Duration,
+    /// Maximum number of re-dispatches to a different upstream address for
+    /// an idempotent request, on top of the initial attempt.
+    pub max_retries: u32,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(2),
+            read_timeout: Duration::from_secs(30),
+            total_timeout: Duration::from_secs(60),
+            max_retries: 2,
+        }
+    }
+}
+
+impl TimeoutConfig {
+    /// Applies the per-connection timeouts to a peer's options. `total_timeout`
+    /// is deliberately not set here: pingora's `total_connection_timeout` only
+    /// bounds connection establishment, not the request/response exchange, so
+    /// it can't implement an end-to-end deadline on its own. That deadline is
+    /// instead tracked directly against `GatewayCtx` (see `upstream_peer`,
+    /// `response_body_filter` and `error_while_proxy` in `mod.rs`).
+    pub fn apply(&self, peer: &mut HttpPeer) {
+        let options = peer
+            .get_mut_peer_options()
+            .expect("peer always has options");
+        options.connection_timeout = Some(self.connect_timeout);
+        options.read_timeout = Some(self.read_timeout);
+    }
+}
+
+/// Per-service overrides for upstream timeouts, keyed by service name.
+/// Services with no entry fall back to the gateway-wide default.
+pub struct TimeoutConfigRegistry {
+    default: TimeoutConfig,
+    per_service: HashMap<EncoreName, TimeoutConfig>,
+}
+
+impl TimeoutConfigRegistry {
+    pub fn new(default: TimeoutConfig) -> Self {
+        Self {
+            default,
+            per_service: HashMap::new(),
+        }
+    }
+
+    pub fn set(&mut self, service: EncoreName, config: TimeoutConfig) {
+        self.per_service.insert(service, config);
+    }
+
+    pub fn resolve(&self, service: &EncoreName) -> TimeoutConfig {
+        self.per_service
+            .get(service)
+            .cloned()
+            .unwrap_or_else(|| self.default.clone())
+    }
+}
+
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Whether a request is safe to re-dispatch to another upstream address on
+/// connect failure or early reset: standard idempotent HTTP methods, or any
+/// request explicitly carrying an idempotency key.
+pub fn is_retryable(method: Method, headers: &http::HeaderMap) -> bool {
+    matches!(method, Method::GET | Method::HEAD | Method::PUT | Method::DELETE)
+        || headers.contains_key(IDEMPOTENCY_KEY_HEADER)
+}
+
+/// Tracks retry attempts for a single request's lifetime.
+#[derive(Default)]
+pub struct RetryState {
+    pub attempts: u32,
+    /// Once any response bytes have started streaming to the downstream
+    /// client, retrying would double-send a partial response, so retries
+    /// are disabled from that point on regardless of method/attempts left.
+    pub response_started: bool,
+}
+
+impl RetryState {
+    pub fn should_retry(&self, config: &TimeoutConfig, retryable: bool) -> bool {
+        retryable && !self.response_started && self.attempts < config.max_retries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_retryable_for_idempotent_methods() {
+        let headers = http::HeaderMap::new();
+        assert!(is_retryable(Method::GET, &headers));
+        assert!(is_retryable(Method::HEAD, &headers));
+        assert!(is_retryable(Method::PUT, &headers));
+        assert!(is_retryable(Method::DELETE, &headers));
+        assert!(!is_retryable(Method::POST, &headers));
+        assert!(!is_retryable(Method::PATCH, &headers));
+    }
+
+    #[test]
+    fn is_retryable_for_non_idempotent_method_with_idempotency_key() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(IDEMPOTENCY_KEY_HEADER, "abc123".parse().unwrap());
+        assert!(is_retryable(Method::POST, &headers));
+    }
+
+    #[test]
+    fn should_retry_respects_response_started_and_attempt_budget() {
+        let config = TimeoutConfig {
+            max_retries: 1,
+            ..TimeoutConfig::default()
+        };
+
+        let fresh = RetryState::default();
+        assert!(fresh.should_retry(&config, true));
+        assert!(!fresh.should_retry(&config, false));
+
+        let exhausted = RetryState {
+            attempts: 1,
+            response_started: false,
+        };
+        assert!(!exhausted.should_retry(&config, true));
+
+        let streaming = RetryState {
+            attempts: 0,
+            response_started: true,
+        };
+        assert!(!streaming.should_retry(&config, true));
+    }
+
+    #[test]
+    fn timeout_config_registry_falls_back_to_default() {
+        let mut registry = TimeoutConfigRegistry::new(TimeoutConfig::default());
+        let overridden = TimeoutConfig {
+            max_retries: 5,
+            ..TimeoutConfig::default()
+        };
+        registry.set(EncoreName::from("svc-a"), overridden);
+
+        assert_eq!(registry.resolve(&EncoreName::from("svc-a")).max_retries, 5);
+        assert_eq!(
+            registry.resolve(&EncoreName::from("svc-b")).max_retries,
+            TimeoutConfig::default().max_retries
+        );
+    }
+}
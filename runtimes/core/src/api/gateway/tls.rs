@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use pingora::tls::pkey::{PKey, Private};
+use pingora::tls::x509::X509;
+use pingora::upstreams::peer::HttpPeer;
+
+use crate::EncoreName;
+
+/// Per-upstream TLS configuration, set per service by whoever constructs the
+/// [`super::Gateway`] (see [`TlsConfigRegistry`]). A service with no
+/// `UpstreamTlsConfig` gets pingora's default TLS behavior (system roots, no
+/// client cert).
+#[derive(Clone)]
+pub struct UpstreamTlsConfig {
+    /// Restricts verification to this root-certificate store instead of the
+    /// system roots, e.g. an internal CA for service-to-service mTLS.
+    pub root_store: Option<Vec<X509>>,
+    /// Client certificate + key presented for mutual TLS.
+    pub client_cert: Option<(X509, PKey<Private>)>,
+    /// Overrides the SNI/verification hostname sent to the upstream,
+    /// instead of the host parsed from its base URL.
+    pub verify_hostname: Option<String>,
+}
+
+impl UpstreamTlsConfig {
+    /// Applies this configuration to a peer's TLS options. Called only for
+    /// upstreams resolved over `https`; callers must check the scheme
+    /// first, same as the default TLS toggle in `upstream_peer`.
+    pub fn apply(&self, peer: &mut HttpPeer) {
+        let options = peer
+            .get_mut_peer_options()
+            .expect("TLS peer always has options");
+
+        if let Some(root_store) = &self.root_store {
+            options.ca = Some(std::sync::Arc::new(root_store.clone()));
+        }
+
+        if let Some((cert, key)) = &self.client_cert {
+            options.set_client_cert_key(cert.clone(), key.clone());
+        }
+
+        if let Some(hostname) = &self.verify_hostname {
+            options.verify_hostname = true;
+            peer.sni = hostname.clone();
+        }
+    }
+}
+
+/// Per-service overrides for upstream mTLS, keyed by service name. Services
+/// with no entry get pingora's default TLS behavior.
+#[derive(Default)]
+pub struct TlsConfigRegistry {
+    per_service: HashMap<EncoreName, UpstreamTlsConfig>,
+}
+
+impl TlsConfigRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, service: EncoreName, config: UpstreamTlsConfig) {
+        self.per_service.insert(service, config);
+    }
+
+    pub fn get(&self, service: &EncoreName) -> Option<&UpstreamTlsConfig> {
+        self.per_service.get(service)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> UpstreamTlsConfig {
+        UpstreamTlsConfig {
+            root_store: None,
+            client_cert: None,
+            verify_hostname: Some("internal.example.com".to_string()),
+        }
+    }
+
+    #[test]
+    fn registry_returns_none_for_services_with_no_override() {
+        let registry = TlsConfigRegistry::new();
+        assert!(registry.get(&EncoreName::from("svc-a")).is_none());
+    }
+
+    #[test]
+    fn registry_returns_the_configured_override() {
+        let mut registry = TlsConfigRegistry::new();
+        registry.set(EncoreName::from("svc-a"), config());
+
+        let resolved = registry.get(&EncoreName::from("svc-a")).unwrap();
+        assert_eq!(
+            resolved.verify_hostname.as_deref(),
+            Some("internal.example.com")
+        );
+        assert!(registry.get(&EncoreName::from("svc-b")).is_none());
+    }
+}